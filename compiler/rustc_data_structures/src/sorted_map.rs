@@ -27,6 +27,12 @@ pub fn new() -> SortedMap<K, V> {
         SortedMap { data: vec![] }
     }
 
+    /// Creates an empty map with space for at least `capacity` elements.
+    #[inline]
+    pub fn with_capacity(capacity: usize) -> SortedMap<K, V> {
+        SortedMap { data: Vec::with_capacity(capacity) }
+    }
+
     /// Construct a `SortedMap` from a presorted set of elements. This is faster
     /// than creating an empty map and then inserting the elements individually.
     ///
@@ -109,6 +115,29 @@ pub fn values(&self) -> impl Iterator<Item = &V> + ExactSizeIterator + DoubleEnd
         self.data.iter().map(|&(_, ref v)| v)
     }
 
+    /// Iterate over values mutably, sorted by key
+    #[inline]
+    pub fn values_mut(
+        &mut self,
+    ) -> impl Iterator<Item = &mut V> + ExactSizeIterator + DoubleEndedIterator {
+        self.data.iter_mut().map(|&mut (_, ref mut v)| v)
+    }
+
+    /// Iterate over elements mutably, sorted by key. Only the values can be
+    /// mutated, so the map stays sorted.
+    #[inline]
+    pub fn iter_mut(
+        &mut self,
+    ) -> impl Iterator<Item = (&K, &mut V)> + ExactSizeIterator + DoubleEndedIterator {
+        self.data.iter_mut().map(|&mut (ref k, ref mut v)| (k, v))
+    }
+
+    /// Reserves space for at least `additional` more elements.
+    #[inline]
+    pub fn reserve(&mut self, additional: usize) {
+        self.data.reserve(additional);
+    }
+
     #[inline]
     pub fn len(&self) -> usize {
         self.data.len()
@@ -137,6 +166,16 @@ pub fn remove_range<R>(&mut self, range: R)
         self.data.splice(start..end, std::iter::empty());
     }
 
+    /// Removes all elements for which `f` returns `false`. The remaining
+    /// elements keep their order, so the map stays sorted.
+    #[inline]
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.data.retain(|&(ref k, ref v)| f(k, v));
+    }
+
     /// Mutate all keys with the given function `f`. This mutation must not
     /// change the sort-order of keys.
     #[inline]
@@ -189,6 +228,54 @@ pub fn insert_presorted(&mut self, mut elements: Vec<(K, V)>) {
         }
     }
 
+    /// Inserts presorted elements produced by an iterator, like `insert_presorted`.
+    /// Instead of inserting the elements one by one where they do not fit in between
+    /// two existing elements, they are merged with the existing elements in a single
+    /// pass, so every existing element is moved at most once. Elements replace
+    /// existing elements with the same key.
+    ///
+    /// It is up to the caller to make sure that the elements are sorted by key
+    /// and that there are no duplicates.
+    #[inline]
+    pub fn insert_presorted_iter<I>(&mut self, elements: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut elements = elements.into_iter().peekable();
+        let start_index = match elements.peek() {
+            Some((first, _)) => match self.lookup_index_for(first) {
+                Ok(index) | Err(index) => index,
+            },
+            None => return,
+        };
+
+        // Only the existing elements after the first new one have to be merged.
+        let mut rest = self.data.split_off(start_index).into_iter().peekable();
+        self.data.reserve(elements.size_hint().0 + rest.len());
+        loop {
+            let take_new = match (elements.peek(), rest.peek()) {
+                (Some((new, _)), Some((old, _))) => match new.cmp(old) {
+                    Ordering::Less => true,
+                    Ordering::Equal => {
+                        rest.next();
+                        true
+                    }
+                    Ordering::Greater => false,
+                },
+                (Some(_), None) => {
+                    self.data.extend(elements);
+                    return;
+                }
+                (None, _) => {
+                    self.data.extend(rest);
+                    return;
+                }
+            };
+            let next = if take_new { elements.next() } else { rest.next() };
+            self.data.push(next.unwrap());
+        }
+    }
+
     /// Looks up the key in `self.data` via `slice::binary_search()`.
     #[inline(always)]
     fn lookup_index_for<Q>(&self, key: &Q) -> Result<usize, usize>
@@ -40,6 +40,30 @@ fn test_insert_and_iter() {
     }
 }
 
+#[test]
+fn test_with_capacity() {
+    let mut map = SortedMap::with_capacity(4);
+    assert!(map.is_empty());
+
+    map.insert(3, 3);
+    map.insert(1, 1);
+
+    assert_eq!(map.into_iter().collect::<Vec<_>>(), vec![(1, 1), (3, 3)]);
+}
+
+#[test]
+fn test_reserve() {
+    let mut map = SortedMap::new();
+    map.insert(3, 3);
+
+    map.reserve(8);
+    assert_eq!(map.len(), 1);
+
+    map.insert(1, 1);
+
+    assert_eq!(map.into_iter().collect::<Vec<_>>(), vec![(1, 1), (3, 3)]);
+}
+
 #[test]
 fn test_get_and_index() {
     let mut map = SortedMap::new();
@@ -107,6 +131,61 @@ fn test_offset_keys() {
     assert_eq!(map, expected);
 }
 
+#[test]
+fn test_retain() {
+    let mut map = SortedMap::new();
+    map.insert(1, 1);
+    map.insert(3, 3);
+    map.insert(6, 6);
+    map.insert(9, 9);
+
+    map.retain(|&k, &v| k != 3 && v != 9);
+
+    let mut expected = SortedMap::new();
+    expected.insert(1, 1);
+    expected.insert(6, 6);
+
+    assert_eq!(map, expected);
+}
+
+#[test]
+fn test_values_mut() {
+    let mut map = SortedMap::new();
+    map.insert(1, 1);
+    map.insert(3, 3);
+    map.insert(6, 6);
+
+    for v in map.values_mut() {
+        *v *= 10;
+    }
+
+    let mut expected = SortedMap::new();
+    expected.insert(1, 10);
+    expected.insert(3, 30);
+    expected.insert(6, 60);
+
+    assert_eq!(map, expected);
+}
+
+#[test]
+fn test_iter_mut() {
+    let mut map = SortedMap::new();
+    map.insert(1, 1);
+    map.insert(3, 3);
+    map.insert(6, 6);
+
+    for (k, v) in map.iter_mut() {
+        *v += *k * 10;
+    }
+
+    let mut expected = SortedMap::new();
+    expected.insert(1, 11);
+    expected.insert(3, 33);
+    expected.insert(6, 66);
+
+    assert_eq!(map, expected);
+}
+
 fn keys(s: SortedMap<u32, u32>) -> Vec<u32> {
     s.into_iter().map(|(k, _)| k).collect::<Vec<u32>>()
 }
@@ -220,3 +299,38 @@ fn test_insert_presorted_at_end() {
     let expected = vec![(1, 1), (2, 2), (3, 3), (8, 8)];
     assert_eq!(elements(map), expected);
 }
+
+#[test]
+fn test_insert_presorted_iter_shuffle() {
+    let mut map = SortedMap::new();
+    map.insert(2, 2);
+    map.insert(7, 7);
+    map.insert(9, 9);
+
+    map.insert_presorted_iter(vec![(1, 1), (3, 3), (7, 0), (8, 8)]);
+
+    let expected = vec![(1, 1), (2, 2), (3, 3), (7, 0), (8, 8), (9, 9)];
+    assert_eq!(elements(map), expected);
+}
+
+#[test]
+fn test_insert_presorted_iter_at_end() {
+    let mut map = SortedMap::new();
+    map.insert(1, 1);
+    map.insert(2, 2);
+
+    map.insert_presorted_iter((3..6).map(|k| (k, k * 10)));
+
+    let expected = vec![(1, 1), (2, 2), (3, 30), (4, 40), (5, 50)];
+    assert_eq!(elements(map), expected);
+}
+
+#[test]
+fn test_insert_presorted_iter_empty() {
+    let mut map = SortedMap::new();
+    map.insert(1, 1);
+
+    map.insert_presorted_iter(vec![]);
+
+    assert_eq!(elements(map), vec![(1, 1)]);
+}
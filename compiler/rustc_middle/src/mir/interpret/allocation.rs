@@ -1,17 +1,24 @@
 //! The virtual memory representation of the MIR interpreter.
 
 use std::borrow::Cow;
+use std::cmp::{self, Ordering};
+use std::collections::BTreeSet;
 use std::convert::TryFrom;
-use std::iter;
-use std::ops::{Deref, DerefMut, Range};
+use std::fmt::{self, Write};
+use std::iter::{self, FromIterator};
+use std::mem;
+use std::ops::{Bound, Deref, DerefMut, Range};
 
 use rustc_ast::Mutability;
+use rustc_data_structures::fx::{FxHashMap, FxHashSet};
 use rustc_data_structures::sorted_map::SortedMap;
+use rustc_data_structures::sync::Lrc;
+use rustc_serialize::leb128;
 use rustc_target::abi::{Align, HasDataLayout, Size};
 
 use super::{
-    read_target_uint, write_target_uint, AllocId, InterpResult, Pointer, Scalar, ScalarMaybeUninit,
-    UninitBytesAccess,
+    read_target_uint, write_target_uint, AllocId, CheckInAllocMsg, InterpResult, Pointer, Scalar,
+    ScalarMaybeUninit, UninitBytesAccess,
 };
 
 #[derive(Clone, Debug, Eq, PartialEq, PartialOrd, Ord, Hash, TyEncodable, TyDecodable)]
@@ -456,11 +463,126 @@ pub fn get_relocations(
         ptr: Pointer<Tag>,
         size: Size,
     ) -> &[(Size, (Tag, AllocId))] {
-        // We have to go back `pointer_size - 1` bytes, as that one would still overlap with
-        // the beginning of this range.
-        let start = ptr.offset.bytes().saturating_sub(cx.data_layout().pointer_size.bytes() - 1);
-        let end = ptr.offset + size; // This does overflow checking.
-        self.relocations.range(Size::from_bytes(start)..end)
+        self.relocations.get_overlapping(cx, ptr.offset, size)
+    }
+
+    /// Returns the number of relocations overlapping with the given pointer-offset pair.
+    /// Like `get_relocations`, a zero-sized range still counts a relocation that spans
+    /// the boundary between `ptr.offset - 1` and `ptr.offset`.
+    pub fn count_relocations(
+        &self,
+        cx: &impl HasDataLayout,
+        ptr: Pointer<Tag>,
+        size: Size,
+    ) -> usize {
+        self.get_relocations(cx, ptr, size).len()
+    }
+
+    /// Returns the number of relocations overlapping with the given pointer-offset pair
+    /// for which `pred` returns `true`. Which relocations are considered is the same as
+    /// for `count_relocations`.
+    pub fn count_relocations_matching(
+        &self,
+        cx: &impl HasDataLayout,
+        ptr: Pointer<Tag>,
+        size: Size,
+        pred: impl Fn((Tag, AllocId)) -> bool,
+    ) -> usize {
+        self.get_relocations(cx, ptr, size).iter().filter(|&&(_, reloc)| pred(reloc)).count()
+    }
+
+    /// Returns the first relocation overlapping with the given pointer-offset pair. Like
+    /// `get_relocations`, a zero-sized range still finds a relocation that spans the
+    /// boundary between `ptr.offset - 1` and `ptr.offset`.
+    pub fn first_relocation(
+        &self,
+        cx: &impl HasDataLayout,
+        ptr: Pointer<Tag>,
+        size: Size,
+    ) -> Option<(Size, (Tag, AllocId))> {
+        self.get_relocations(cx, ptr, size).first().copied()
+    }
+
+    /// Returns the distinct allocations that relocations overlapping with the given
+    /// pointer-offset pair point to, each once and in increasing order. This collects
+    /// the targets into a set first, so it allocates.
+    pub fn distinct_relocation_targets(
+        &self,
+        cx: &impl HasDataLayout,
+        ptr: Pointer<Tag>,
+        size: Size,
+    ) -> impl Iterator<Item = AllocId> {
+        let targets: BTreeSet<_> = self
+            .get_relocations(cx, ptr, size)
+            .iter()
+            .map(|&(_, (_, alloc_id))| alloc_id)
+            .collect();
+        targets.into_iter()
+    }
+
+    /// Returns whether `self` and `other` have the same relocations overlapping with the given
+    /// pointer-offset pair. Relocations straddling the edges of the range have to match as
+    /// well, including the parts outside of the range.
+    pub fn relocations_eq_range(
+        &self,
+        other: &Allocation<Tag, Extra>,
+        cx: &impl HasDataLayout,
+        ptr: Pointer<Tag>,
+        size: Size,
+    ) -> bool
+    where
+        Tag: PartialEq,
+    {
+        self.get_relocations(cx, ptr, size) == other.get_relocations(cx, ptr, size)
+    }
+
+    /// Panics if there are relocations overlapping with the given pointer-offset pair,
+    /// listing their offsets. Meant for tests, where this gives a better message than
+    /// asserting that `get_relocations` is empty.
+    #[track_caller]
+    pub fn assert_no_relocations(&self, cx: &impl HasDataLayout, ptr: Pointer<Tag>, size: Size) {
+        let relocations = self.get_relocations(cx, ptr, size);
+        if !relocations.is_empty() {
+            let offsets: Vec<_> = relocations.iter().map(|&(offset, _)| offset.bytes()).collect();
+            panic!(
+                "expected no relocations in {}..{}, but found relocations at offsets {:?}",
+                ptr.offset.bytes(),
+                (ptr.offset + size).bytes(), // `Size` addition
+                offsets
+            );
+        }
+    }
+
+    /// Returns the maximal sub-ranges of the given memory range that are free of
+    /// relocations. Relocations that start before `ptr.offset` but overlap with the
+    /// range are taken into account.
+    pub fn relocation_free_ranges(
+        &self,
+        cx: &impl HasDataLayout,
+        ptr: Pointer<Tag>,
+        size: Size,
+    ) -> impl Iterator<Item = Range<Size>> + '_ {
+        let pointer_size = cx.data_layout().pointer_size;
+        let end = ptr.offset + size; // `Size` addition
+        let mut relocations = self.get_relocations(cx, ptr, size).iter();
+        let mut cur = ptr.offset;
+        iter::from_fn(move || {
+            for &(offset, _) in &mut relocations {
+                let gap = cur..offset;
+                // Relocations do not overlap, so this never moves `cur` backwards.
+                cur = offset + pointer_size; // `Size` addition
+                if gap.start < gap.end {
+                    return Some(gap);
+                }
+            }
+            if cur < end {
+                let gap = cur..end;
+                cur = end;
+                Some(gap)
+            } else {
+                None
+            }
+        })
     }
 
     /// Checks that there are no relocations overlapping with the given range.
@@ -484,18 +606,39 @@ fn check_relocations(
     /// uninitialized. This is a somewhat odd "spooky action at a distance",
     /// but it allows strictly more code to run than if we would just error
     /// immediately in that case.
+    ///
+    /// For an empty range both edges coincide, so a relocation overlapping that
+    /// single position is removed and marked as uninitialized as well.
     fn clear_relocations(
         &mut self,
         cx: &impl HasDataLayout,
         ptr: Pointer<Tag>,
         size: Size,
     ) -> InterpResult<'tcx> {
+        self.clear_relocations_reporting(cx, ptr, size)?;
+        Ok(())
+    }
+
+    /// Removes all relocations inside the given range like `clear_relocations`, and returns
+    /// whether there were any to remove. Callers can use this to skip work when the range
+    /// was free of relocations already.
+    pub fn clear_relocations_reporting(
+        &mut self,
+        cx: &impl HasDataLayout,
+        ptr: Pointer<Tag>,
+        size: Size,
+    ) -> InterpResult<'tcx, bool> {
+        // Avoid computing the range at all if there is nothing to remove.
+        if self.relocations.is_empty() {
+            return Ok(false);
+        }
+
         // Find the start and end of the given range and its outermost relocations.
         let (first, last) = {
             // Find all relocations overlapping the given range.
             let relocations = self.get_relocations(cx, ptr, size);
             if relocations.is_empty() {
-                return Ok(());
+                return Ok(false);
             }
 
             (
@@ -518,9 +661,84 @@ fn clear_relocations(
         // Forget all the relocations.
         self.relocations.remove_range(first..last);
 
+        Ok(true)
+    }
+
+    /// Removes all relocations inside the given range like `clear_relocations`, including
+    /// the ones overlapping with the edges, and returns what the removed relocations pointed
+    /// to. The result may contain duplicates, and the same allocations may still be pointed
+    /// to by relocations outside of the range; reconciling that is up to the caller.
+    pub fn clear_relocations_collecting(
+        &mut self,
+        cx: &impl HasDataLayout,
+        ptr: Pointer<Tag>,
+        size: Size,
+    ) -> InterpResult<'tcx, Vec<(Tag, AllocId)>> {
+        let removed = self.get_relocations(cx, ptr, size).iter().map(|&(_, reloc)| reloc).collect();
+        self.clear_relocations(cx, ptr, size)?;
+        Ok(removed)
+    }
+
+    /// Removes all relocations inside the given range, like `clear_relocations`.
+    /// However, if there are relocations overlapping with the edges, this errors
+    /// with `PartialPointerOverwrite` instead of marking the bytes they cover as
+    /// uninitialized. Nothing is modified in that case.
+    pub fn clear_relocations_strict(
+        &mut self,
+        cx: &impl HasDataLayout,
+        ptr: Pointer<Tag>,
+        size: Size,
+    ) -> InterpResult<'tcx> {
+        // Find the start and end of the given range and its outermost relocations.
+        let (first, last) = {
+            // Find all relocations overlapping the given range.
+            let relocations = self.get_relocations(cx, ptr, size);
+            if relocations.is_empty() {
+                return Ok(());
+            }
+
+            (
+                relocations.first().unwrap().0,
+                relocations.last().unwrap().0 + cx.data_layout().pointer_size,
+            )
+        };
+        let start = ptr.offset;
+        let end = start + size; // `Size` addition
+
+        if first < start {
+            throw_unsup!(PartialPointerOverwrite {
+                ptr: Pointer::new(ptr.alloc_id, first),
+                size: cx.data_layout().pointer_size,
+            });
+        }
+        if last > end {
+            let last_start = last - cx.data_layout().pointer_size; // `Size` subtraction
+            throw_unsup!(PartialPointerOverwrite {
+                ptr: Pointer::new(ptr.alloc_id, last_start),
+                size: cx.data_layout().pointer_size,
+            });
+        }
+
+        // Forget all the relocations.
+        self.relocations.remove_range(first..last);
+
         Ok(())
     }
 
+    /// Removes all relocations inside the given range like `clear_relocations_strict`, and
+    /// returns them sorted by offset, e.g. for re-inserting them later. Errors in the same
+    /// cases, in which case nothing is removed.
+    pub fn drain_relocations(
+        &mut self,
+        cx: &impl HasDataLayout,
+        ptr: Pointer<Tag>,
+        size: Size,
+    ) -> InterpResult<'tcx, Vec<(Size, (Tag, AllocId))>> {
+        let drained = self.get_relocations(cx, ptr, size).to_vec();
+        self.clear_relocations_strict(cx, ptr, size)?;
+        Ok(drained)
+    }
+
     /// Errors if there are relocations overlapping with the edges of the
     /// given memory range.
     #[inline]
@@ -534,6 +752,420 @@ fn check_relocation_edges(
         self.check_relocations(cx, ptr.offset(size, cx)?, Size::ZERO)?;
         Ok(())
     }
+
+    /// Returns the start of a relocation overlapping with the edges of the given memory
+    /// range, i.e., of a relocation that writing to this range would only partially
+    /// overwrite. Returns `None` if both edges are free of relocations.
+    pub fn partial_relocation_on_edges(
+        &self,
+        cx: &impl HasDataLayout,
+        ptr: Pointer<Tag>,
+        size: Size,
+    ) -> Option<Size> {
+        let end = ptr.offset + size; // `Size` addition
+        let end = Pointer::new_with_tag(ptr.alloc_id, end, ptr.tag);
+        self.get_relocations(cx, ptr, Size::ZERO)
+            .first()
+            .or_else(|| self.get_relocations(cx, end, Size::ZERO).first())
+            .map(|&(offset, _)| offset)
+    }
+
+    /// Inserts a relocation for the pointer-sized value starting at `ptr.offset`.
+    ///
+    /// Unlike inserting into `Relocations` directly, this checks that no other relocation
+    /// overlaps with the new one and errors with `PartialPointerOverwrite` otherwise.
+    pub fn try_insert_relocation(
+        &mut self,
+        cx: &impl HasDataLayout,
+        ptr: Pointer<Tag>,
+        reloc: (Tag, AllocId),
+    ) -> InterpResult<'tcx> {
+        let pointer_size = cx.data_layout().pointer_size;
+        if let Some(&(overlapping, _)) = self.get_relocations(cx, ptr, pointer_size).first() {
+            throw_unsup!(PartialPointerOverwrite {
+                ptr: Pointer::new(ptr.alloc_id, overlapping),
+                size: pointer_size,
+            });
+        }
+        self.relocations.insert(ptr.offset, reloc);
+        Ok(())
+    }
+
+    /// Inserts a relocation for the pointer-sized value starting at `ptr.offset`, removing
+    /// whatever relocations were there before, like a pointer store would.
+    ///
+    /// Like for a store, relocations that only partially overlap with the new one are removed
+    /// as well, and the bytes they cover outside of the new pointer are marked as uninitialized;
+    /// see `clear_relocations`.
+    pub fn insert_relocation_overwriting(
+        &mut self,
+        cx: &impl HasDataLayout,
+        ptr: Pointer<Tag>,
+        reloc: (Tag, AllocId),
+    ) -> InterpResult<'tcx> {
+        self.clear_relocations(cx, ptr, cx.data_layout().pointer_size)?;
+        self.relocations.insert(ptr.offset, reloc);
+        Ok(())
+    }
+
+    /// Inserts all relocations of `other` into this allocation, with their offsets
+    /// shifted to start at `dest.offset`.
+    ///
+    /// Errors with `PartialPointerOverwrite` if any of the shifted relocations would
+    /// overlap with a relocation that is already present; in that case nothing is inserted.
+    pub fn merge_relocations(
+        &mut self,
+        cx: &impl HasDataLayout,
+        other: &Relocations<Tag>,
+        dest: Pointer<Tag>,
+    ) -> InterpResult<'tcx> {
+        let pointer_size = cx.data_layout().pointer_size;
+        let mut new_relocations = Vec::with_capacity(other.len());
+        for &(offset, reloc) in other.iter() {
+            let dest_ptr = dest.offset(offset, cx)?;
+            if let Some(&(overlapping, _)) =
+                self.get_relocations(cx, dest_ptr, pointer_size).first()
+            {
+                throw_unsup!(PartialPointerOverwrite {
+                    ptr: Pointer::new(dest.alloc_id, overlapping),
+                    size: pointer_size,
+                });
+            }
+            new_relocations.push((dest_ptr.offset, reloc));
+        }
+        // `other` is sorted and shifting preserves that, so this is presorted as well.
+        self.relocations.insert_presorted(new_relocations);
+        Ok(())
+    }
+
+    /// Errors with `PartialPointerOverwrite` if a relocation straddles `offset`, i.e. if
+    /// cutting this allocation at `offset` would split a pointer. `alloc_id` identifies this
+    /// allocation in that error.
+    fn check_no_straddling_relocation(
+        &self,
+        cx: &impl HasDataLayout,
+        alloc_id: AllocId,
+        offset: Size,
+    ) -> InterpResult<'tcx> {
+        if let Some(&(straddling, _)) =
+            self.relocations.get_overlapping(cx, offset, Size::ZERO).first()
+        {
+            throw_unsup!(PartialPointerOverwrite {
+                ptr: Pointer::new(alloc_id, straddling),
+                size: cx.data_layout().pointer_size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Removes all relocations at or after `at` and returns them, with their offsets rebased
+    /// to start at zero. `alloc_id` identifies this allocation.
+    ///
+    /// Errors with `PartialPointerOverwrite` if a relocation straddles `at`; nothing is
+    /// modified in that case.
+    pub fn split_off_relocations(
+        &mut self,
+        cx: &impl HasDataLayout,
+        alloc_id: AllocId,
+        at: Size,
+    ) -> InterpResult<'tcx, Relocations<Tag>> {
+        self.check_no_straddling_relocation(cx, alloc_id, at)?;
+        let split_off = self
+            .relocations
+            .range(at..)
+            .iter()
+            .map(|&(offset, reloc)| (offset - at, reloc)) // `Size` subtraction
+            .collect();
+        self.relocations.remove_range(at..);
+        Ok(Relocations::from_presorted(split_off))
+    }
+
+    /// Removes all relocations at or after `new_len`, e.g. before shrinking the allocation to
+    /// `new_len` bytes. `alloc_id` identifies this allocation.
+    ///
+    /// Errors with `PartialPointerOverwrite` if a relocation straddles `new_len`; nothing is
+    /// modified in that case.
+    pub fn truncate_relocations(
+        &mut self,
+        cx: &impl HasDataLayout,
+        alloc_id: AllocId,
+        new_len: Size,
+    ) -> InterpResult<'tcx> {
+        self.check_no_straddling_relocation(cx, alloc_id, new_len)?;
+        self.relocations.remove_range(new_len..);
+        Ok(())
+    }
+
+    /// Removes all relocations before `cut` and shifts the remaining ones down by `cut`, e.g.
+    /// before removing the first `cut` bytes of the allocation. This is the inverse of
+    /// `Relocations::rebase`. `alloc_id` identifies this allocation.
+    ///
+    /// Errors with `PartialPointerOverwrite` if a relocation straddles `cut`; nothing is
+    /// modified in that case.
+    pub fn shift_relocations_down(
+        &mut self,
+        cx: &impl HasDataLayout,
+        alloc_id: AllocId,
+        cut: Size,
+    ) -> InterpResult<'tcx> {
+        self.check_no_straddling_relocation(cx, alloc_id, cut)?;
+        self.relocations.remove_range(..cut);
+        // All remaining offsets are at least `cut`, and subtracting the same amount from
+        // every offset preserves their order.
+        self.relocations.offset_keys(|offset| *offset = *offset - cut);
+        Ok(())
+    }
+
+    /// Returns the relocation starting at `ptr.offset`, if any. Unlike looking up the offset
+    /// in `relocations()`, this errors with `PointerOutOfBounds` if `ptr.offset` is not
+    /// inside the allocation, instead of conflating that with there being no relocation.
+    pub fn get_relocation_checked(
+        &self,
+        ptr: Pointer<Tag>,
+    ) -> InterpResult<'tcx, Option<(Tag, AllocId)>> {
+        if ptr.offset >= self.size {
+            throw_ub!(PointerOutOfBounds {
+                ptr: ptr.erase_tag(),
+                msg: CheckInAllocMsg::MemoryAccessTest,
+                allocation_size: self.size,
+            });
+        }
+        Ok(self.relocations.get(&ptr.offset).copied())
+    }
+
+    /// Returns the relocations inside the given memory range, with their offsets rebased
+    /// to start at zero. This is a single copy of the range as `prepare_relocation_copy`
+    /// would prepare it for a destination at offset zero, but as `Relocations`.
+    ///
+    /// Like copying such a range, this errors if there are relocations overlapping with
+    /// the edges of the range.
+    pub fn clone_relocation_range(
+        &self,
+        cx: &impl HasDataLayout,
+        ptr: Pointer<Tag>,
+        size: Size,
+    ) -> InterpResult<'tcx, Relocations<Tag>> {
+        self.check_relocation_edges(cx, ptr, size)?;
+        let relocations = self
+            .get_relocations(cx, ptr, size)
+            .iter()
+            .map(|&(offset, reloc)| (offset - ptr.offset, reloc)) // `Size` subtraction
+            .collect();
+        Ok(Relocations::from_presorted(relocations))
+    }
+
+    /// Replaces the relocations inside the given memory range by the ones of a prepared
+    /// relocation copy. This clears the range like `clear_relocations_strict` and then applies
+    /// the copy like `mark_relocation_range_checked`, erroring in the same cases. If either
+    /// step fails, nothing is modified.
+    pub fn replace_relocations(
+        &mut self,
+        cx: &impl HasDataLayout,
+        ptr: Pointer<Tag>,
+        size: Size,
+        relocations: AllocationRelocations<Tag>,
+    ) -> InterpResult<'tcx> {
+        let removed = self.drain_relocations(cx, ptr, size)?;
+        if let Err(err) = self.mark_relocation_range_checked(cx, ptr.alloc_id, relocations) {
+            // Roll back the clearing. The removed relocations were inside the range, which
+            // has not been touched since.
+            self.relocations.insert_presorted(removed);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Copies the relocations of the `size` bytes at `src` to `dest` within this allocation,
+    /// like `memmove` does for the bytes: the two ranges may overlap, and relocations in the
+    /// source range that are not overwritten stay where they are. Relocations previously in
+    /// the destination range are removed.
+    ///
+    /// Errors if there are relocations overlapping with the edges of the source range, or
+    /// with `PartialPointerOverwrite` if there are relocations overlapping with the edges of
+    /// the destination range. Nothing is modified in either case.
+    pub fn move_relocations_within(
+        &mut self,
+        cx: &impl HasDataLayout,
+        src: Pointer<Tag>,
+        size: Size,
+        dest: Pointer<Tag>,
+    ) -> InterpResult<'tcx> {
+        // Collect the relocations before touching the destination, so that an overlap of the
+        // ranges does not matter.
+        let moved = self.clone_relocation_range(cx, src, size)?;
+        self.clear_relocations_strict(cx, dest, size)?;
+        let moved = moved
+            .iter()
+            .map(|&(offset, reloc)| (dest.offset + offset, reloc)) // `Size` addition
+            .collect();
+        // All moved relocations lie inside the destination range, which is now empty.
+        self.relocations.insert_presorted(moved);
+        Ok(())
+    }
+
+    /// Inserts the given relocations, which must be sorted by offset, like
+    /// `try_insert_relocation` does for a single one. `alloc_id` identifies this allocation.
+    ///
+    /// Errors with `PartialPointerOverwrite` if two of the new relocations overlap with each
+    /// other or if one of them overlaps with a relocation that is already present; in that
+    /// case nothing is inserted.
+    pub fn try_insert_relocations_presorted(
+        &mut self,
+        cx: &impl HasDataLayout,
+        alloc_id: AllocId,
+        relocations: Vec<(Size, (Tag, AllocId))>,
+    ) -> InterpResult<'tcx> {
+        let pointer_size = cx.data_layout().pointer_size;
+        for [(fst, _), (snd, _)] in relocations.array_windows() {
+            debug_assert!(fst < snd);
+            if *snd - *fst < pointer_size {
+                throw_unsup!(PartialPointerOverwrite {
+                    ptr: Pointer::new(alloc_id, *fst),
+                    size: pointer_size,
+                });
+            }
+        }
+        for &(offset, _) in &relocations {
+            if let Some(&(overlapping, _)) =
+                self.relocations.get_overlapping(cx, offset, pointer_size).first()
+            {
+                throw_unsup!(PartialPointerOverwrite {
+                    ptr: Pointer::new(alloc_id, overlapping),
+                    size: pointer_size,
+                });
+            }
+        }
+        self.relocations.insert_presorted(relocations);
+        Ok(())
+    }
+
+    /// Removes all relocations outside of the given memory range, keeping the offsets of the
+    /// remaining ones, e.g. before an allocation is logically shrunk at both ends.
+    ///
+    /// Errors with `PartialPointerOverwrite` if a relocation straddles an edge of the range;
+    /// nothing is modified in that case.
+    pub fn trim_relocations_to(
+        &mut self,
+        cx: &impl HasDataLayout,
+        ptr: Pointer<Tag>,
+        size: Size,
+    ) -> InterpResult<'tcx> {
+        if let Some(straddling) = self.partial_relocation_on_edges(cx, ptr, size) {
+            throw_unsup!(PartialPointerOverwrite {
+                ptr: Pointer::new(ptr.alloc_id, straddling),
+                size: cx.data_layout().pointer_size,
+            });
+        }
+        let end = ptr.offset + size; // `Size` addition
+        self.relocations.remove_range(end..);
+        self.relocations.remove_range(..ptr.offset);
+        Ok(())
+    }
+
+    /// Inserts all relocations of `delta` into this allocation at their offsets, replacing
+    /// relocations that start at the same offset, like layered writes of the pointers in
+    /// `delta` would. `alloc_id` identifies this allocation.
+    ///
+    /// Errors with `PartialPointerOverwrite` if a relocation of `delta` overlaps with an
+    /// existing relocation at a different offset, as that one could only be overwritten
+    /// partially; nothing is modified in that case.
+    pub fn overlay_relocations(
+        &mut self,
+        cx: &impl HasDataLayout,
+        alloc_id: AllocId,
+        delta: &Relocations<Tag>,
+    ) -> InterpResult<'tcx> {
+        let pointer_size = cx.data_layout().pointer_size;
+        for &(offset, _) in delta.iter() {
+            for &(overlapping, _) in self.relocations.get_overlapping(cx, offset, pointer_size) {
+                if overlapping != offset {
+                    throw_unsup!(PartialPointerOverwrite {
+                        ptr: Pointer::new(alloc_id, overlapping),
+                        size: pointer_size,
+                    });
+                }
+            }
+        }
+        // Inserting presorted elements replaces existing ones with the same key.
+        self.relocations.insert_presorted(delta.iter().copied().collect());
+        Ok(())
+    }
+
+    /// Removes all relocations inside the given pointer-offset pairs, which must be sorted
+    /// and disjoint. The result is the same as calling `clear_relocations` for each range in
+    /// turn, including marking the parts of the relocations overlapping with the edges that
+    /// lie outside of the range as uninitialized, but the relocations that remain are moved
+    /// only once.
+    pub fn clear_relocation_ranges(
+        &mut self,
+        cx: &impl HasDataLayout,
+        ranges: &[(Pointer<Tag>, Size)],
+    ) {
+        debug_assert!(ranges
+            .array_windows()
+            .all(|[(fst, fst_size), (snd, _)]| fst.offset + *fst_size <= snd.offset));
+        if self.relocations.is_empty() {
+            return;
+        }
+        let pointer_size = cx.data_layout().pointer_size;
+
+        // Find the relocations to remove for each range, like `clear_relocations` does.
+        let mut removed: Vec<Range<Size>> = Vec::new();
+        for &(ptr, size) in ranges {
+            let start = ptr.offset;
+            let end = start + size; // `Size` addition
+            let mut relocations = self.relocations.get_overlapping(cx, start, size);
+            // A relocation straddling the gap to the previous range has already been removed
+            // for that one.
+            if let (Some(prev), Some(&(first, _))) = (removed.last(), relocations.first()) {
+                if first < prev.end {
+                    relocations = &relocations[1..];
+                }
+            }
+            let (first, last) = match (relocations.first(), relocations.last()) {
+                (Some(&(first, _)), Some(&(last, _))) => (first, last),
+                _ => continue,
+            };
+            let last = last + pointer_size; // `Size` addition
+            if first < start {
+                self.init_mask.set_range(first, start, false);
+            }
+            if last > end {
+                self.init_mask.set_range(end, last, false);
+            }
+            removed.push(first..last);
+        }
+
+        // Avoid touching the relocations at all if no range overlaps with any of them, as that
+        // would copy them if they are shared.
+        if removed.is_empty() {
+            return;
+        }
+        let mut removed = removed.into_iter().peekable();
+        self.relocations.retain(|offset, _| {
+            // Both the relocations and the removed ranges are sorted, so ranges ending at or
+            // before `offset` do not matter for this or any later relocation.
+            while removed.peek().map_or(false, |range| range.end <= offset) {
+                removed.next();
+            }
+            !removed.peek().map_or(false, |range| range.start <= offset)
+        });
+    }
+
+    /// Removes the relocations that lie entirely inside the given memory range. Unlike
+    /// `clear_relocations`, relocations overlapping with the edges of the range are left
+    /// untouched, and so are the bytes they cover.
+    pub fn clear_interior_relocations(
+        &mut self,
+        cx: &impl HasDataLayout,
+        ptr: Pointer<Tag>,
+        size: Size,
+    ) {
+        let contained = self.relocations.get_contained(cx, ptr.offset, size);
+        if let (Some(&(first, _)), Some(&(last, _))) = (contained.first(), contained.last()) {
+            self.relocations.remove_range(first..=last);
+        }
+    }
 }
 
 /// Uninitialized bytes.
@@ -660,18 +1292,52 @@ pub fn mark_compressed_init_range(
 }
 
 /// Relocations.
+///
+/// Every relocation applies to the `pointer_size` bytes starting at its offset, and
+/// relocations never overlap; consecutive offsets are hence at least `pointer_size` apart.
+///
+/// The map is shared between clones and only copied when one of them gets mutated, since
+/// allocations are frequently cloned without their relocations ever changing afterwards.
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, TyEncodable, TyDecodable)]
-pub struct Relocations<Tag = (), Id = AllocId>(SortedMap<Size, (Tag, Id)>);
+pub struct Relocations<Tag = (), Id = AllocId>(Lrc<SortedMap<Size, (Tag, Id)>>);
 
 impl<Tag, Id> Relocations<Tag, Id> {
     pub fn new() -> Self {
-        Relocations(SortedMap::new())
+        Relocations(Lrc::new(SortedMap::new()))
+    }
+
+    /// Creates empty relocations with space for at least `capacity` relocations, to avoid
+    /// growing the map repeatedly when the number of pointers is known in advance.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Relocations(Lrc::new(SortedMap::with_capacity(capacity)))
     }
 
     // The caller must guarantee that the given relocations are already sorted
     // by address and contain no duplicates.
     pub fn from_presorted(r: Vec<(Size, (Tag, Id))>) -> Self {
-        Relocations(SortedMap::from_presorted_elements(r))
+        Relocations(Lrc::new(SortedMap::from_presorted_elements(r)))
+    }
+
+    /// Returns an estimate of the heap memory used by these relocations. This only
+    /// accounts for the stored entries, not for any spare capacity of the buffer.
+    pub fn approximate_heap_bytes(&self) -> usize {
+        self.0.len() * mem::size_of::<(Size, (Tag, Id))>()
+    }
+}
+
+impl<Tag, Id> Default for Relocations<Tag, Id> {
+    fn default() -> Self {
+        Relocations::new()
+    }
+}
+
+/// Builds relocations from entries in arbitrary order. When several entries have the same
+/// offset, only one of them is kept. This sorts the entries, so it is meant for setting up
+/// relocations, not for hot paths. As no data layout is available here, the caller has to
+/// make sure that the relocations do not overlap, e.g. by calling `validate` afterwards.
+impl<Tag, Id> FromIterator<(Size, (Tag, Id))> for Relocations<Tag, Id> {
+    fn from_iter<T: IntoIterator<Item = (Size, (Tag, Id))>>(iter: T) -> Self {
+        Relocations(Lrc::new(iter.into_iter().collect()))
     }
 }
 
@@ -683,53 +1349,848 @@ fn deref(&self) -> &Self::Target {
     }
 }
 
-impl<Tag> DerefMut for Relocations<Tag> {
+impl<Tag: Clone> DerefMut for Relocations<Tag> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        Lrc::make_mut(&mut self.0)
+    }
+}
+
+/// Returns the range of offsets at which relocations overlapping with the `size` bytes
+/// starting at `offset` start.
+fn overlapping_window(cx: &impl HasDataLayout, offset: Size, size: Size) -> Range<Size> {
+    // We have to go back `pointer_size - 1` bytes, as that one would still overlap with
+    // the beginning of this range.
+    let start = offset.bytes().saturating_sub(cx.data_layout().pointer_size.bytes() - 1);
+    let end = offset + size; // This does overflow checking.
+    Size::from_bytes(start)..end
+}
+
+impl<Tag: Copy> Relocations<Tag> {
+    /// Creates relocations from a map obtained from an untrusted source, e.g. a cache
+    /// file, checking that the relocations do not overlap. Returns a description of the
+    /// overlap otherwise, like `validate`.
+    pub fn from_decoded_validated(
+        cx: &impl HasDataLayout,
+        relocations: SortedMap<Size, (Tag, AllocId)>,
+    ) -> Result<Self, String> {
+        let relocations = Relocations(Lrc::new(relocations));
+        relocations.validate_spacing(cx)?;
+        Ok(relocations)
+    }
+
+    /// Returns all relocations overlapping with the `size` bytes starting at `offset`. See
+    /// `Allocation::get_relocations`.
+    pub fn get_overlapping(
+        &self,
+        cx: &impl HasDataLayout,
+        offset: Size,
+        size: Size,
+    ) -> &[(Size, (Tag, AllocId))] {
+        if self.is_empty() {
+            return &[];
+        }
+        self.range(overlapping_window(cx, offset, size))
+    }
+
+    /// Returns the relocations that lie entirely inside the `size` bytes starting at
+    /// `offset`. Unlike `get_overlapping`, relocations that overlap with the edges of the
+    /// range without fitting into it are not included; these are the relocations that
+    /// `Allocation::clear_interior_relocations` removes.
+    pub fn get_contained(
+        &self,
+        cx: &impl HasDataLayout,
+        offset: Size,
+        size: Size,
+    ) -> &[(Size, (Tag, AllocId))] {
+        let end = offset + size; // `Size` addition
+
+        // The last relocation that fits into the range starts `pointer_size` bytes before its end.
+        match end.bytes().checked_sub(cx.data_layout().pointer_size.bytes()) {
+            Some(last_start) if last_start >= offset.bytes() => {
+                self.range(offset..=Size::from_bytes(last_start))
+            }
+            _ => &[],
+        }
+    }
+
+    /// Returns the relocation covering the byte at `offset`, together with the offset
+    /// at which that relocation starts. Unlike `get`, this also finds relocations that
+    /// start up to `pointer_size - 1` bytes before `offset`.
+    pub fn get_containing(
+        &self,
+        cx: &impl HasDataLayout,
+        offset: Size,
+    ) -> Option<(Size, (Tag, AllocId))> {
+        // Relocations do not overlap, so there is at most one candidate.
+        self.get_overlapping(cx, offset, Size::from_bytes(1)).last().copied()
+    }
+
+    /// Returns the relocation starting at `offset` like `get`, but only if `offset` is
+    /// aligned for pointers according to the data layout. This catches lookups at offsets
+    /// where a pointer is not expected to start.
+    pub fn get_aligned(&self, cx: &impl HasDataLayout, offset: Size) -> Option<(Tag, AllocId)> {
+        if !offset.is_aligned(cx.data_layout().pointer_align.abi) {
+            return None;
+        }
+        self.get(&offset).copied()
+    }
+
+    /// Returns the first relocation starting strictly after `after`, together with its
+    /// offset. This is a binary search, so it allows skipping over relocation-free
+    /// regions without visiting them.
+    pub fn next_after(&self, after: Size) -> Option<(Size, (Tag, AllocId))> {
+        self.range((Bound::Excluded(after), Bound::Unbounded)).first().copied()
+    }
+
+    /// Returns the last relocation starting at or before `at`, together with its offset.
+    /// This is the counterpart of `next_after` for scanning backwards.
+    pub fn prev_at_or_before(&self, at: Size) -> Option<(Size, (Tag, AllocId))> {
+        self.range(..=at).last().copied()
+    }
+
+    /// Returns the `n`-th relocation in order of offsets, together with its offset, or
+    /// `None` if there are at most `n` relocations (see `len`). The relocations are stored
+    /// in a sorted vector, so this takes constant time.
+    pub fn nth(&self, n: usize) -> Option<(Size, (Tag, AllocId))> {
+        self.iter().nth(n).copied()
+    }
+
+    /// Returns whether a relocation starts exactly at `offset` and `offset` is aligned to
+    /// `align`, i.e. whether an aligned load at `offset` reads an intact pointer.
+    pub fn is_aligned_relocation(&self, offset: Size, align: Align) -> bool {
+        offset.is_aligned(align) && self.contains_key(&offset)
+    }
+
+    /// Returns the offset of the first relocation, if any.
+    pub fn min_offset(&self) -> Option<Size> {
+        self.keys().next().copied()
+    }
+
+    /// Returns the offset at which the last relocation starts, if any. Use `span` to also
+    /// take into account where it ends.
+    pub fn max_offset(&self) -> Option<Size> {
+        self.keys().next_back().copied()
+    }
+
+    /// Returns the smallest range covering all relocations, from the start of the first
+    /// one to the end of the last one, or `None` if there are no relocations.
+    pub fn span(&self, cx: &impl HasDataLayout) -> Option<Range<Size>> {
+        let first = self.min_offset()?;
+        let last = self.max_offset()?;
+        Some(first..last + cx.data_layout().pointer_size) // `Size` addition
+    }
+
+    /// Shifts the offsets of all relocations up by `delta`. Errors without modifying
+    /// anything if that would move a relocation beyond the maximum object size.
+    pub fn offset_all<'tcx>(&mut self, cx: &impl HasDataLayout, delta: Size) -> InterpResult<'tcx> {
+        if let Some(&last) = self.keys().next_back() {
+            // The last relocation has to end within the maximum object size, not just start there.
+            let end = last
+                .checked_add(delta, cx)
+                .and_then(|last| last.checked_add(cx.data_layout().pointer_size, cx));
+            if end.is_none() {
+                throw_ub!(PointerArithOverflow);
+            }
+        }
+        // Adding the same amount to every offset preserves their order.
+        self.offset_keys(|offset| *offset += delta);
+        Ok(())
+    }
+
+    /// Returns these relocations with all offsets shifted up by `new_base`, e.g. when
+    /// embedding an allocation into a larger one at that offset. Errors like `offset_all`.
+    pub fn rebase<'tcx>(
+        mut self,
+        cx: &impl HasDataLayout,
+        new_base: Size,
+    ) -> InterpResult<'tcx, Self> {
+        self.offset_all(cx, new_base)?;
+        Ok(self)
+    }
+
+    /// Returns the offsets of all relocations pointing to `alloc_id`. The relocations
+    /// are keyed by offset, so this is a linear scan over all of them.
+    pub fn offsets_for(&self, alloc_id: AllocId) -> impl Iterator<Item = Size> + '_ {
+        self.iter().filter(move |&&(_, (_, id))| id == alloc_id).map(|&(offset, _)| offset)
+    }
+
+    /// Returns the offsets of the first two adjacent relocations that are less than
+    /// `pointer_size` apart and hence overlap, or `None` if there are no such relocations.
+    pub fn check_spacing(&self, cx: &impl HasDataLayout) -> Option<(Size, Size)> {
+        let pointer_size = cx.data_layout().pointer_size;
+        self.iter()
+            .as_slice()
+            .array_windows()
+            // Subtracting cannot overflow, unlike adding `pointer_size` to an offset that was
+            // decoded from an untrusted source.
+            .find(|[(fst, _), (snd, _)]| *snd - *fst < pointer_size)
+            .map(|[(fst, _), (snd, _)]| (*fst, *snd))
+    }
+
+    /// Like `check_spacing`, but returns a description of the overlap as error.
+    fn validate_spacing(&self, cx: &impl HasDataLayout) -> Result<(), String> {
+        match self.check_spacing(cx) {
+            Some((fst, snd)) => {
+                Err(format!("relocations at offsets {} and {} overlap", fst.bytes(), snd.bytes()))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the offsets of all relocations that do not start at a multiple of
+    /// `pointer_size`. This is empty for allocations that only had pointers written to
+    /// aligned places, so a non-empty result can point at a bug in how they were written.
+    pub fn misaligned(&self, cx: &impl HasDataLayout) -> Vec<Size> {
+        let pointer_size = cx.data_layout().pointer_size.bytes();
+        self.keys().filter(|offset| offset.bytes() % pointer_size != 0).copied().collect()
+    }
+
+    /// Checks the invariants of the relocations of an allocation of size `alloc_size`:
+    /// relocations must not overlap and must lie entirely inside the allocation. Returns a
+    /// description of the first violation that was found.
+    pub fn validate(&self, cx: &impl HasDataLayout, alloc_size: Size) -> Result<(), String> {
+        let pointer_size = cx.data_layout().pointer_size;
+        self.validate_spacing(cx)?;
+        if let Some(&last) = self.keys().next_back() {
+            // An end that overflows clearly lies outside of the allocation as well.
+            if last.checked_add(pointer_size, cx).map_or(true, |end| end > alloc_size) {
+                return Err(format!(
+                    "relocation at offset {} exceeds allocation of size {}",
+                    last.bytes(),
+                    alloc_size.bytes()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes all relocations and returns them, sorted by offset. They can be put back
+    /// with `restore`.
+    pub fn clear_all(&mut self) -> Vec<(Size, (Tag, AllocId))> {
+        let relocations = mem::replace(&mut self.0, Lrc::new(SortedMap::new()));
+        // Avoid copying the map if it is not shared with a clone.
+        let relocations = Lrc::try_unwrap(relocations).unwrap_or_else(|shared| (*shared).clone());
+        relocations.into_iter().collect()
+    }
+
+    /// Re-inserts relocations previously removed by `clear_all`. The caller must make sure
+    /// that they do not overlap with any relocation that was inserted in the meantime.
+    pub fn restore(&mut self, relocations: Vec<(Size, (Tag, AllocId))>) {
+        self.insert_presorted(relocations);
+    }
+
+    /// Computes summary statistics of these relocations.
+    pub fn stats(&self) -> RelocationStats {
+        let distinct_targets =
+            self.values().map(|&(_, alloc_id)| alloc_id).collect::<FxHashSet<_>>();
+        RelocationStats { count: self.len(), distinct_targets: distinct_targets.len() }
+    }
+
+    /// Returns how many relocations point to each allocation that is pointed to at all.
+    /// This hashes every relocation, so it is linear in the number of relocations.
+    pub fn target_histogram(&self) -> FxHashMap<AllocId, usize> {
+        let mut histogram = FxHashMap::default();
+        for &(_, alloc_id) in self.values() {
+            *histogram.entry(alloc_id).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Returns the number of distinct pairs of tag and target among the relocations. Unlike
+    /// `stats().distinct_targets`, relocations to the same allocation with different tags are
+    /// counted separately. The tags only need to be `Ord`, not `Hash`: this copies all pairs
+    /// into a temporary vector and sorts it, which takes `O(n log n)` time.
+    pub fn distinct_tagged_targets(&self) -> usize
+    where
+        Tag: Ord,
+    {
+        let mut relocs: Vec<_> = self.values().copied().collect();
+        relocs.sort_unstable();
+        relocs.dedup();
+        relocs.len()
+    }
+
+    /// Returns the allocation all relocations point to, if there are relocations and they
+    /// all point to the same allocation. This stops at the first relocation pointing
+    /// elsewhere.
+    pub fn single_target(&self) -> Option<AllocId> {
+        let mut targets = self.values().map(|&(_, alloc_id)| alloc_id);
+        let first = targets.next()?;
+        targets.all(|alloc_id| alloc_id == first).then_some(first)
+    }
+
+    /// Returns whether `self` and `other` point to the same allocations the same number of
+    /// times, regardless of the offsets of the relocations. This sorts the targets of both,
+    /// so it allocates.
+    pub fn same_target_multiset(&self, other: &Relocations<Tag>) -> bool {
+        if self.len() != other.len() {
+            return false;
+        }
+        let sorted_targets = |relocations: &Relocations<Tag>| {
+            let mut targets: Vec<_> = relocations.values().map(|&(_, alloc_id)| alloc_id).collect();
+            targets.sort_unstable();
+            targets
+        };
+        sorted_targets(self) == sorted_targets(other)
+    }
+
+    /// Calls `f` with the offset and a mutable reference to the tag and target of every
+    /// relocation, in order of their offsets. Offsets cannot be changed this way, so the
+    /// relocations stay sorted.
+    pub fn for_each_mut(&mut self, mut f: impl FnMut(Size, &mut (Tag, AllocId))) {
+        for (&offset, reloc) in self.iter_mut() {
+            f(offset, reloc);
+        }
+    }
+
+    /// Replaces the target of every relocation by the result of applying `f` to it.
+    /// Offsets are kept as they are, so no re-sorting is necessary.
+    pub fn remap_alloc_ids(&mut self, mut f: impl FnMut(AllocId) -> AllocId) {
+        for (_, alloc_id) in self.values_mut() {
+            *alloc_id = f(*alloc_id);
+        }
+    }
+
+    /// Replaces the target of every relocation that has an entry in `mapping` by the
+    /// target given there, leaving all other relocations untouched.
+    ///
+    /// If no relocation is affected, the map is not touched at all, so it stays shared with
+    /// any clones of it.
+    pub fn remap_subset(&mut self, mapping: &FxHashMap<AllocId, AllocId>) {
+        if mapping.is_empty() || !self.values().any(|(_, alloc_id)| mapping.contains_key(alloc_id))
+        {
+            return;
+        }
+        self.remap_alloc_ids(|alloc_id| mapping.get(&alloc_id).copied().unwrap_or(alloc_id));
+    }
+
+    /// Reserves space for at least `additional` more relocations, e.g. before inserting
+    /// many pointers one by one.
+    pub fn reserve(&mut self, additional: usize) {
+        (**self).reserve(additional);
+    }
+
+    /// Returns the relocation starting at each of the offsets `0, stride, 2 * stride, ...`
+    /// below `alloc_size`, for estimating how densely an allocation is populated with
+    /// pointers. Relocations starting in between the sampled offsets are missed.
+    ///
+    /// Panics if `stride` is zero.
+    pub fn sample(&self, stride: Size, alloc_size: Size) -> Vec<Option<(Tag, AllocId)>> {
+        (0..alloc_size.bytes())
+            .step_by(stride.bytes_usize())
+            .map(|offset| self.get(&Size::from_bytes(offset)).copied())
+            .collect()
+    }
+
+    /// Removes all relocations for which `keep` returns `false`, e.g. those pointing to
+    /// allocations that have been freed.
+    pub fn retain(&mut self, mut keep: impl FnMut(Size, (Tag, AllocId)) -> bool) {
+        (**self).retain(|&offset, &reloc| keep(offset, reloc));
+    }
+
+    /// Returns the offsets at which both `self` and `other` have a relocation, regardless
+    /// of what the relocations at that offset are.
+    pub fn common_offsets<'a>(
+        &'a self,
+        other: &'a Relocations<Tag>,
+    ) -> impl Iterator<Item = Size> + 'a {
+        let mut ours = self.keys().peekable();
+        let mut theirs = other.keys().peekable();
+        // Merge-join the two sorted lists, stopping as soon as one of them is exhausted.
+        iter::from_fn(move || loop {
+            match ours.peek()?.cmp(theirs.peek()?) {
+                Ordering::Less => {
+                    ours.next();
+                }
+                Ordering::Greater => {
+                    theirs.next();
+                }
+                Ordering::Equal => {
+                    theirs.next();
+                    return ours.next().copied();
+                }
+            }
+        })
+    }
+
+    /// Converts these relocations to relocations with a different tag type by applying `f`
+    /// to every tag, stopping at the first error. Offsets are kept as they are, so no
+    /// re-sorting is necessary.
+    pub fn try_map_tags<Tag2, E>(
+        &self,
+        mut f: impl FnMut(Tag) -> Result<Tag2, E>,
+    ) -> Result<Relocations<Tag2>, E> {
+        let relocations = self
+            .iter()
+            .map(|&(offset, (tag, alloc_id))| Ok((offset, (f(tag)?, alloc_id))))
+            .collect::<Result<_, E>>()?;
+        Ok(Relocations::from_presorted(relocations))
+    }
+}
+
+impl<Tag: Copy + PartialEq> Relocations<Tag> {
+    /// Returns the offsets at which `self` and `other` disagree, together with the
+    /// relocation (if any) that each of them has at that offset. Offsets that have
+    /// the same relocation in both lists are skipped.
+    pub fn diff<'a>(
+        &'a self,
+        other: &'a Relocations<Tag>,
+    ) -> impl Iterator<Item = (Size, Option<(Tag, AllocId)>, Option<(Tag, AllocId)>)> + 'a {
+        let mut old = self.iter().peekable();
+        let mut new = other.iter().peekable();
+        // Merge-join the two sorted lists.
+        iter::from_fn(move || loop {
+            let (offset, old_reloc, new_reloc) = match (old.peek(), new.peek()) {
+                (None, None) => return None,
+                (Some(&&(offset, reloc)), None) => {
+                    old.next();
+                    (offset, Some(reloc), None)
+                }
+                (None, Some(&&(offset, reloc))) => {
+                    new.next();
+                    (offset, None, Some(reloc))
+                }
+                (Some(&&(old_offset, old_reloc)), Some(&&(new_offset, new_reloc))) => {
+                    match old_offset.cmp(&new_offset) {
+                        Ordering::Less => {
+                            old.next();
+                            (old_offset, Some(old_reloc), None)
+                        }
+                        Ordering::Greater => {
+                            new.next();
+                            (new_offset, None, Some(new_reloc))
+                        }
+                        Ordering::Equal => {
+                            old.next();
+                            new.next();
+                            (old_offset, Some(old_reloc), Some(new_reloc))
+                        }
+                    }
+                }
+            };
+            if old_reloc != new_reloc {
+                return Some((offset, old_reloc, new_reloc));
+            }
+        })
+    }
+
+    /// Groups relocations that are directly adjacent to each other and equal into runs, and
+    /// returns the range covered by each run together with its relocation. This describes
+    /// e.g. an array of identical pointers by a single entry.
+    pub fn runs<'a>(
+        &'a self,
+        cx: &'a impl HasDataLayout,
+    ) -> impl Iterator<Item = (Range<Size>, (Tag, AllocId))> + 'a {
+        let pointer_size = cx.data_layout().pointer_size;
+        let mut relocations = self.iter().peekable();
+        iter::from_fn(move || {
+            let &(start, reloc) = relocations.next()?;
+            let mut end = start + pointer_size; // `Size` addition
+            while let Some(&&(offset, next)) = relocations.peek() {
+                if offset != end || next != reloc {
+                    break;
+                }
+                relocations.next();
+                end += pointer_size; // `Size` addition
+            }
+            Some((start..end, reloc))
+        })
     }
 }
 
+impl<Tag: Copy + fmt::Debug> Relocations<Tag> {
+    /// Renders the relocations of an allocation of size `alloc_size` for debugging, with
+    /// one line per pointer-sized chunk of the allocation. Unlike the derived `Debug`, this
+    /// makes relocations stand out that are not aligned to a chunk.
+    pub fn dump(&self, cx: &impl HasDataLayout, alloc_size: Size) -> String {
+        let pointer_size = cx.data_layout().pointer_size;
+        let mut out = String::new();
+        let mut start = Size::ZERO;
+        while start < alloc_size {
+            let end = cmp::min(start + pointer_size, alloc_size); // `Size` addition
+            match *self.get_overlapping(cx, start, end - start) {
+                [] => writeln!(out, "{:#06x}: no relocation", start.bytes()),
+                [(offset, (tag, alloc_id))] if offset == start && end - start == pointer_size => {
+                    writeln!(
+                        out,
+                        "{:#06x}: pointer to {} with tag {:?}",
+                        start.bytes(),
+                        alloc_id,
+                        tag
+                    )
+                }
+                ref overlapping => {
+                    let offsets = overlapping
+                        .iter()
+                        .map(|(offset, _)| format!("{:#x}", offset.bytes()))
+                        .collect::<Vec<_>>();
+                    writeln!(
+                        out,
+                        "{:#06x}: parts of pointers at {}",
+                        start.bytes(),
+                        offsets.join(", ")
+                    )
+                }
+            }
+            .unwrap();
+            start = end;
+        }
+        out
+    }
+}
+
+impl Relocations {
+    /// Encodes these relocations in a compact binary format that, unlike `TyEncodable`, does
+    /// not depend on the internals of the compiler and can hence be persisted across builds.
+    ///
+    /// It consists of the number of relocations followed by the offset and the `AllocId` of
+    /// each relocation in order, all as unsigned LEB128.
+    pub fn encode_compact(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        leb128::write_usize_leb128(&mut out, self.len());
+        for &(offset, ((), alloc_id)) in self.iter() {
+            leb128::write_u64_leb128(&mut out, offset.bytes());
+            leb128::write_u64_leb128(&mut out, alloc_id.0);
+        }
+        out
+    }
+
+    /// Decodes relocations produced by `encode_compact`. As no data layout is available
+    /// here, this only checks that the offsets are strictly increasing, not that the
+    /// relocations do not overlap; use `validate` for that.
+    pub fn decode_compact(mut bytes: &[u8]) -> Result<Self, String> {
+        let len = read_compact_u64(&mut bytes)?;
+        // Do not trust `len` for preallocating, the input might be corrupted.
+        let mut relocations = Vec::new();
+        for _ in 0..len {
+            let offset = Size::from_bytes(read_compact_u64(&mut bytes)?);
+            let alloc_id = AllocId(read_compact_u64(&mut bytes)?);
+            if let Some(&(prev, _)) = relocations.last() {
+                if offset <= prev {
+                    return Err(format!(
+                        "relocation offsets {} and {} are not strictly increasing",
+                        prev.bytes(),
+                        offset.bytes()
+                    ));
+                }
+            }
+            relocations.push((offset, ((), alloc_id)));
+        }
+        if !bytes.is_empty() {
+            return Err(format!("{} trailing bytes after the relocations", bytes.len()));
+        }
+        Ok(Relocations::from_presorted(relocations))
+    }
+}
+
+/// Reads an unsigned LEB128 value from the front of `bytes` and advances it past that value.
+fn read_compact_u64(bytes: &mut &[u8]) -> Result<u64, String> {
+    // `read_u64_leb128` panics on truncated input, so make sure the value ends in time.
+    // A `u64` takes at most 10 bytes.
+    let last = match bytes.iter().take(10).position(|&byte| byte & 0x80 == 0) {
+        Some(last) => last,
+        None => return Err("truncated or overlong LEB128 value".to_string()),
+    };
+    // The 10th byte only holds the highest bit of a `u64`, `read_u64_leb128` would silently
+    // drop the others.
+    if last == 9 && bytes[9] > 1 {
+        return Err("LEB128 value does not fit into 64 bits".to_string());
+    }
+    let (value, read) = leb128::read_u64_leb128(bytes);
+    *bytes = &bytes[read..];
+    Ok(value)
+}
+
+/// The relocations of an allocation that will not be modified anymore, e.g. once const-eval
+/// has finished. They are stored as a shared slice, which unlike the map of `Relocations`
+/// has no spare capacity. Only reading them is supported; use `thaw` to modify them again.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct FrozenRelocations<Tag = ()>(Lrc<[(Size, (Tag, AllocId))]>);
+
+impl<Tag: Copy> Relocations<Tag> {
+    /// Turns these relocations into their read-only form. This always copies them into a
+    /// new slice of exactly the right size, dropping the spare capacity of the map.
+    pub fn freeze(mut self) -> FrozenRelocations<Tag> {
+        FrozenRelocations(self.clear_all().into())
+    }
+}
+
+impl<Tag: Copy> FrozenRelocations<Tag> {
+    /// Turns these relocations back into the mutable form, copying them.
+    pub fn thaw(&self) -> Relocations<Tag> {
+        Relocations::from_presorted(self.0.to_vec())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Iterates over the relocations, sorted by offset.
+    pub fn iter(&self) -> impl Iterator<Item = &(Size, (Tag, AllocId))> + ExactSizeIterator {
+        self.0.iter()
+    }
+
+    /// Returns the relocation starting at `offset`, if any.
+    pub fn get(&self, offset: Size) -> Option<(Tag, AllocId)> {
+        let index = self.0.binary_search_by_key(&offset, |&(offset, _)| offset).ok()?;
+        Some(self.0[index].1)
+    }
+
+    /// Returns all relocations overlapping with the `size` bytes starting at `offset`, like
+    /// `Allocation::get_relocations` does.
+    pub fn get_overlapping(
+        &self,
+        cx: &impl HasDataLayout,
+        offset: Size,
+        size: Size,
+    ) -> &[(Size, (Tag, AllocId))] {
+        let window = overlapping_window(cx, offset, size);
+        // Offsets are unique, so an exact match is the first index at or after the key.
+        let index_of = |key| match self.0.binary_search_by_key(&key, |&(offset, _)| offset) {
+            Ok(index) | Err(index) => index,
+        };
+        &self.0[index_of(window.start)..index_of(window.end)]
+    }
+
+    /// Returns the allocations pointed to by the relocations, sorted by the offset of the
+    /// relocation and possibly with duplicates.
+    pub fn targets(&self) -> impl Iterator<Item = AllocId> + '_ {
+        self.0.iter().map(|&(_, (_, alloc_id))| alloc_id)
+    }
+}
+
+/// Summary statistics of the relocations of an allocation, see `Relocations::stats`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RelocationStats {
+    /// The number of relocations.
+    pub count: usize,
+    /// The number of distinct allocations the relocations point to.
+    pub distinct_targets: usize,
+}
+
 /// A partial, owned list of relocations to transfer into another allocation.
+///
+/// Only the relocations of a single repetition are stored; they are replicated when the
+/// copy is applied, so that copying a small value many times does not need a buffer
+/// holding every repetition.
 pub struct AllocationRelocations<Tag> {
+    /// The relocations of the first repetition, already shifted to the destination.
     relative_relocations: Vec<(Size, (Tag, AllocId))>,
+    /// The distance between the starts of two consecutive repetitions.
+    stride: Size,
+    /// The number of repetitions.
+    length: u64,
+}
+
+impl<Tag> AllocationRelocations<Tag> {
+    /// Creates a relocation copy from relocations whose offsets already refer to the
+    /// destination allocation, to be applied with `mark_relocation_range`.
+    ///
+    /// The caller must guarantee that the relocations are sorted by offset and contain
+    /// no duplicates.
+    pub fn from_presorted(relocations: Vec<(Size, (Tag, AllocId))>) -> Self {
+        debug_assert!(relocations.array_windows().all(|[fst, snd]| fst.0 < snd.0));
+        AllocationRelocations { relative_relocations: relocations, stride: Size::ZERO, length: 1 }
+    }
+
+    /// Returns whether applying this copy inserts no relocations at all.
+    fn is_empty(&self) -> bool {
+        self.length == 0 || self.relative_relocations.is_empty()
+    }
+
+    /// Returns the offset of the last relocation of the last repetition, if any. Every
+    /// repetition lies after the previous one, so this is the highest offset of the copy.
+    fn last_offset(&self) -> Option<Size> {
+        let last = self.relative_relocations.last()?.0;
+        let last_repetition = self.length.checked_sub(1)?;
+        Some(last + self.stride * last_repetition) // `Size` operations
+    }
+
+    /// Iterates over the relocations of all repetitions, sorted by offset, with their offsets
+    /// shifted up by another `extra_shift`.
+    fn repetitions(&self, extra_shift: Size) -> impl Iterator<Item = (Size, (Tag, AllocId))> + '_
+    where
+        Tag: Copy,
+    {
+        let AllocationRelocations { ref relative_relocations, stride, length } = *self;
+        (0..length).flat_map(move |i| {
+            // compute offset for current repetition
+            let shift = stride * i + extra_shift; // `Size` operations
+            relative_relocations.iter().map(move |&(offset, reloc)| (offset + shift, reloc))
+        })
+    }
 }
 
 impl<Tag: Copy, Extra> Allocation<Tag, Extra> {
-    pub fn prepare_relocation_copy(
+    /// Prepares copying the relocations of the `size` bytes at `src` to `dest`, `length` times
+    /// in a row. Relocations overlapping with the edges of the source range are not handled;
+    /// the caller has to reject those before applying the copy, but if one would be shifted
+    /// below offset zero, this errors with `PointerArithOverflow` already. Copying an empty
+    /// range copies nothing, independently of `length`.
+    pub fn prepare_relocation_copy<'tcx>(
         &self,
         cx: &impl HasDataLayout,
         src: Pointer<Tag>,
         size: Size,
         dest: Pointer<Tag>,
         length: u64,
-    ) -> AllocationRelocations<Tag> {
+    ) -> InterpResult<'tcx, AllocationRelocations<Tag>> {
         let relocations = self.get_relocations(cx, src, size);
-        if relocations.is_empty() {
-            return AllocationRelocations { relative_relocations: Vec::new() };
+        // A relocation found for an empty range merely overlaps its position, which is an
+        // edge of the range that the caller has to check anyway; it is not part of the copy.
+        if relocations.is_empty() || size == Size::ZERO {
+            return Ok(AllocationRelocations {
+                relative_relocations: Vec::new(),
+                stride: size,
+                length: 0,
+            });
         }
 
-        let mut new_relocations = Vec::with_capacity(relocations.len() * (length as usize));
-
-        for i in 0..length {
-            new_relocations.extend(relocations.iter().map(|&(offset, reloc)| {
-                // compute offset for current repetition
-                let dest_offset = dest.offset + size * i; // `Size` operations
-                (
-                    // shift offsets from source allocation to destination allocation
-                    (offset + dest_offset) - src.offset, // `Size` operations
-                    reloc,
-                )
-            }));
+        let relative_relocations: Vec<_> = relocations
+            .iter()
+            .map(|&(offset, reloc)| {
+                // shift offsets from source allocation to destination allocation
+                // A relocation straddling the start of the source range begins before it, so
+                // the subtraction can underflow if `dest` is close enough to the start.
+                let shifted = offset
+                    .checked_add(dest.offset, cx)
+                    .and_then(|shifted| shifted.bytes().checked_sub(src.offset.bytes()));
+                match shifted {
+                    Some(shifted) => Ok((Size::from_bytes(shifted), reloc)),
+                    None => throw_ub!(PointerArithOverflow),
+                }
+            })
+            .collect::<InterpResult<'tcx, _>>()?;
+
+        // Make sure that the offsets of all repetitions can be computed without overflowing,
+        // so that applying the copy does not have to check again.
+        if let (Some(&(last, _)), Some(last_repetition)) =
+            (relative_relocations.last(), length.checked_sub(1))
+        {
+            let last = size
+                .checked_mul(last_repetition, cx)
+                .and_then(|shift| last.checked_add(shift, cx))
+                .and_then(|last| last.checked_add(cx.data_layout().pointer_size, cx));
+            if last.is_none() {
+                throw_ub!(PointerArithOverflow);
+            }
         }
 
-        AllocationRelocations { relative_relocations: new_relocations }
+        Ok(AllocationRelocations { relative_relocations, stride: size, length })
+    }
+
+    /// Like `prepare_relocation_copy`, but additionally checks that all copied relocations
+    /// lie entirely inside the destination allocation, which has size `dest_size`.
+    pub fn prepare_relocation_copy_checked<'tcx>(
+        &self,
+        cx: &impl HasDataLayout,
+        src: Pointer<Tag>,
+        size: Size,
+        dest: Pointer<Tag>,
+        length: u64,
+        dest_size: Size,
+    ) -> InterpResult<'tcx, AllocationRelocations<Tag>> {
+        let relocations = self.prepare_relocation_copy(cx, src, size, dest, length)?;
+        if let Some(last) = relocations.last_offset() {
+            if last + cx.data_layout().pointer_size > dest_size {
+                throw_ub!(PointerOutOfBounds {
+                    ptr: Pointer::new(dest.alloc_id, last),
+                    msg: CheckInAllocMsg::MemoryAccessTest,
+                    allocation_size: dest_size,
+                });
+            }
+        }
+        Ok(relocations)
     }
 
     /// Applies a relocation copy.
     /// The affected range, as defined in the parameters to `prepare_relocation_copy` is expected
     /// to be clear of relocations.
     pub fn mark_relocation_range(&mut self, relocations: AllocationRelocations<Tag>) {
-        self.relocations.insert_presorted(relocations.relative_relocations);
+        // Copies of plain bytes are the common case; do not unshare the map for them.
+        if relocations.is_empty() {
+            return;
+        }
+        // Each repetition lies after the previous one, so all of them together are presorted.
+        self.relocations.insert_presorted_iter(relocations.repetitions(Size::ZERO));
+    }
+
+    /// Applies a relocation copy like `mark_relocation_range`, but with all offsets shifted
+    /// up by another `extra_shift`. The copy is only borrowed, so the same copy can be applied
+    /// at several places without preparing it again. Errors without modifying anything if
+    /// the shift would move a relocation beyond the maximum object size.
+    pub fn mark_relocation_range_at<'tcx>(
+        &mut self,
+        cx: &impl HasDataLayout,
+        relocations: &AllocationRelocations<Tag>,
+        extra_shift: Size,
+    ) -> InterpResult<'tcx> {
+        if let Some(last) = relocations.last_offset() {
+            // Like `prepare_relocation_copy`, check where the last relocation ends.
+            let end = last
+                .checked_add(extra_shift, cx)
+                .and_then(|last| last.checked_add(cx.data_layout().pointer_size, cx));
+            if end.is_none() {
+                throw_ub!(PointerArithOverflow);
+            }
+        }
+        // Like in `mark_relocation_range`, do not unshare the map if there is nothing to insert.
+        if !relocations.is_empty() {
+            self.relocations.insert_presorted_iter(relocations.repetitions(extra_shift));
+        }
+        Ok(())
+    }
+
+    /// Applies a relocation copy like `mark_relocation_range`, but first checks that all
+    /// relocations of the copy lie entirely inside this allocation. Errors with
+    /// `PointerOutOfBounds` otherwise, in which case nothing is inserted. `alloc_id`
+    /// identifies this allocation in that error.
+    pub fn mark_relocation_range_within<'tcx>(
+        &mut self,
+        cx: &impl HasDataLayout,
+        alloc_id: AllocId,
+        relocations: AllocationRelocations<Tag>,
+    ) -> InterpResult<'tcx> {
+        if let Some(last) = relocations.last_offset() {
+            if last + cx.data_layout().pointer_size > self.size {
+                throw_ub!(PointerOutOfBounds {
+                    ptr: Pointer::new(alloc_id, last),
+                    msg: CheckInAllocMsg::MemoryAccessTest,
+                    allocation_size: self.size,
+                });
+            }
+        }
+        self.mark_relocation_range(relocations);
+        Ok(())
+    }
+
+    /// Applies a relocation copy like `mark_relocation_range`, but first checks that the
+    /// affected range is indeed clear of relocations. Errors with `PartialPointerOverwrite`
+    /// otherwise, in which case nothing is inserted. `alloc_id` identifies the destination
+    /// allocation in that error.
+    pub fn mark_relocation_range_checked<'tcx>(
+        &mut self,
+        cx: &impl HasDataLayout,
+        alloc_id: AllocId,
+        relocations: AllocationRelocations<Tag>,
+    ) -> InterpResult<'tcx> {
+        let pointer_size = cx.data_layout().pointer_size;
+        for (offset, _) in relocations.repetitions(Size::ZERO) {
+            if let Some(&(overlapping, _)) =
+                self.relocations.get_overlapping(cx, offset, pointer_size).first()
+            {
+                throw_unsup!(PartialPointerOverwrite {
+                    ptr: Pointer::new(alloc_id, overlapping),
+                    size: pointer_size,
+                });
+            }
+        }
+        self.mark_relocation_range(relocations);
+        Ok(())
     }
 }
 
@@ -881,3 +2342,6 @@ fn bit_index(bits: Size) -> (usize, usize) {
     let b = bits % InitMask::BLOCK_SIZE;
     (usize::try_from(a).unwrap(), usize::try_from(b).unwrap())
 }
+
+#[cfg(test)]
+mod tests;
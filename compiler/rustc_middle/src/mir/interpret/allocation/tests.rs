@@ -0,0 +1,337 @@
+use super::*;
+
+use crate::mir::interpret::{InterpError, UndefinedBehaviorInfo, UnsupportedOpInfo};
+use rustc_target::abi::TargetDataLayout;
+
+fn ptr(offset: u64) -> Pointer {
+    Pointer::new(AllocId(0), Size::from_bytes(offset))
+}
+
+/// Creates an allocation of `size` bytes with pointers to `AllocId(target)` written at the
+/// given offsets.
+fn alloc_with_pointers(size: u64, pointers: &[(u64, u64)]) -> Allocation {
+    let cx = TargetDataLayout::default();
+    let mut alloc = Allocation::uninit(Size::from_bytes(size), Align::from_bytes(8).unwrap());
+    for &(offset, target) in pointers {
+        let val = Scalar::Ptr(Pointer::new(AllocId(target), Size::ZERO));
+        assert!(alloc.write_ptr_sized(&cx, ptr(offset), val.into()).is_ok());
+    }
+    alloc
+}
+
+/// Returns the offsets and targets of the relocations of `alloc`.
+fn relocations(alloc: &Allocation) -> Vec<(u64, u64)> {
+    alloc
+        .relocations()
+        .iter()
+        .map(|&(offset, ((), alloc_id))| (offset.bytes(), alloc_id.0))
+        .collect()
+}
+
+/// Returns the error of `res`, panicking if there is none.
+fn err_kind<'tcx, T>(res: InterpResult<'tcx, T>) -> InterpError<'tcx> {
+    match res {
+        Ok(_) => panic!("expected an error"),
+        Err(err) => err.kind,
+    }
+}
+
+#[test]
+fn relocation_counts() {
+    let cx = TargetDataLayout::default();
+    let mut alloc = alloc_with_pointers(64, &[]);
+    assert_eq!(alloc.relocations().len(), 0);
+
+    assert!(alloc.try_insert_relocation(&cx, ptr(0), ((), AllocId(1))).is_ok());
+    assert!(alloc.try_insert_relocation(&cx, ptr(16), ((), AllocId(2))).is_ok());
+    assert!(alloc.try_insert_relocation(&cx, ptr(32), ((), AllocId(1))).is_ok());
+    assert_eq!(alloc.relocations().len(), 3);
+    assert_eq!(alloc.relocations().stats(), RelocationStats { count: 3, distinct_targets: 2 });
+    assert_eq!(alloc.count_relocations(&cx, ptr(8), Size::from_bytes(16)), 1);
+
+    // A failed insertion does not change the count.
+    assert!(alloc.try_insert_relocation(&cx, ptr(20), ((), AllocId(3))).is_err());
+    assert_eq!(alloc.relocations().len(), 3);
+
+    // Clearing also removes the relocation that only overlaps with the end of the range.
+    assert!(alloc.clear_relocations(&cx, ptr(8), Size::from_bytes(12)).is_ok());
+    assert_eq!(alloc.relocations().len(), 2);
+    assert_eq!(alloc.relocations().stats(), RelocationStats { count: 2, distinct_targets: 1 });
+    assert_eq!(alloc.count_relocations(&cx, ptr(0), Size::from_bytes(64)), 2);
+
+    // Copying the relocation at 0 three times into another allocation.
+    let copy = alloc.prepare_relocation_copy(&cx, ptr(0), Size::from_bytes(16), ptr(0), 3);
+    let mut dest = alloc_with_pointers(64, &[(56, 4)]);
+    dest.mark_relocation_range(copy.ok().unwrap());
+    assert_eq!(dest.relocations().len(), 4);
+    assert_eq!(dest.relocations().stats(), RelocationStats { count: 4, distinct_targets: 2 });
+    assert_eq!(dest.count_relocations(&cx, ptr(0), Size::from_bytes(48)), 3);
+    assert_eq!(relocations(&dest), vec![(0, 1), (16, 1), (32, 1), (56, 4)]);
+    assert_eq!(alloc.relocations().len(), 2);
+}
+
+#[test]
+fn replace_relocations_rolls_back() {
+    let cx = TargetDataLayout::default();
+    let mut alloc = alloc_with_pointers(64, &[(0, 1), (16, 2), (40, 3)]);
+    let original = alloc.relocations().clone();
+
+    // The relocations in the range are cleared first, and only then does the relocation at 36
+    // of the replacement turn out to overlap with the one at 40, which lies outside the range.
+    let replacement = AllocationRelocations::from_presorted(vec![
+        (Size::from_bytes(8), ((), AllocId(4))),
+        (Size::from_bytes(36), ((), AllocId(5))),
+    ]);
+    let res = alloc.replace_relocations(&cx, ptr(0), Size::from_bytes(32), replacement);
+    assert!(matches!(
+        err_kind(res),
+        InterpError::Unsupported(UnsupportedOpInfo::PartialPointerOverwrite { .. })
+    ));
+    assert_eq!(alloc.relocations(), &original);
+    assert_eq!(relocations(&alloc), vec![(0, 1), (16, 2), (40, 3)]);
+
+    // Without the conflict, the relocations in the range are replaced.
+    let replacement =
+        AllocationRelocations::from_presorted(vec![(Size::from_bytes(8), ((), AllocId(4)))]);
+    assert!(alloc.replace_relocations(&cx, ptr(0), Size::from_bytes(32), replacement).is_ok());
+    assert_eq!(relocations(&alloc), vec![(8, 4), (40, 3)]);
+}
+
+#[test]
+fn move_relocations_within_forward_overlap() {
+    let cx = TargetDataLayout::default();
+    let mut alloc = alloc_with_pointers(64, &[(0, 1), (8, 2), (40, 3)]);
+    // Moving 0..16 to 8..24 overwrites the relocation at 8 only after it has been read.
+    assert!(alloc.move_relocations_within(&cx, ptr(0), Size::from_bytes(16), ptr(8)).is_ok());
+    assert_eq!(relocations(&alloc), vec![(0, 1), (8, 1), (16, 2), (40, 3)]);
+}
+
+#[test]
+fn move_relocations_within_backward_overlap() {
+    let cx = TargetDataLayout::default();
+    let mut alloc = alloc_with_pointers(64, &[(8, 1), (16, 2), (40, 3)]);
+    // Moving 8..24 to 0..16 leaves the relocation at 16, which is not overwritten.
+    assert!(alloc.move_relocations_within(&cx, ptr(8), Size::from_bytes(16), ptr(0)).is_ok());
+    assert_eq!(relocations(&alloc), vec![(0, 1), (8, 2), (16, 2), (40, 3)]);
+}
+
+#[test]
+fn move_relocations_within_straddled_edges() {
+    let cx = TargetDataLayout::default();
+    let mut alloc = alloc_with_pointers(64, &[(4, 1), (36, 2)]);
+
+    // The relocation at 4 straddles the start of the source range.
+    let res = alloc.move_relocations_within(&cx, ptr(8), Size::from_bytes(16), ptr(48));
+    assert!(matches!(
+        err_kind(res),
+        InterpError::Unsupported(UnsupportedOpInfo::ReadPointerAsBytes)
+    ));
+    assert_eq!(relocations(&alloc), vec![(4, 1), (36, 2)]);
+
+    // The relocation at 36 straddles the start of the destination range.
+    let res = alloc.move_relocations_within(&cx, ptr(16), Size::from_bytes(16), ptr(40));
+    assert!(matches!(
+        err_kind(res),
+        InterpError::Unsupported(UnsupportedOpInfo::PartialPointerOverwrite { .. })
+    ));
+    assert_eq!(relocations(&alloc), vec![(4, 1), (36, 2)]);
+}
+
+#[test]
+fn prepare_relocation_copy_overflow() {
+    let cx = TargetDataLayout::default();
+    let alloc = alloc_with_pointers(64, &[(0, 1), (8, 2)]);
+    for &length in &[u64::MAX / 8, 1 << 44] {
+        let res = alloc.prepare_relocation_copy(&cx, ptr(0), Size::from_bytes(16), ptr(0), length);
+        assert!(matches!(
+            err_kind(res),
+            InterpError::UndefinedBehavior(UndefinedBehaviorInfo::PointerArithOverflow)
+        ));
+    }
+
+    // The relocation at 4 straddles the start of the source range, so shifting it to a
+    // destination at 0 would move it before the start of the allocation.
+    let alloc = alloc_with_pointers(64, &[(4, 1)]);
+    let res = alloc.prepare_relocation_copy(&cx, ptr(8), Size::from_bytes(16), ptr(0), 1);
+    assert!(matches!(
+        err_kind(res),
+        InterpError::UndefinedBehavior(UndefinedBehaviorInfo::PointerArithOverflow)
+    ));
+}
+
+#[test]
+fn merge_relocations_disjoint() {
+    let cx = TargetDataLayout::default();
+    let mut alloc = alloc_with_pointers(64, &[(0, 1), (32, 2)]);
+    let other = alloc_with_pointers(16, &[(0, 5), (8, 6)]);
+    assert!(alloc.merge_relocations(&cx, other.relocations(), ptr(8)).is_ok());
+    assert_eq!(relocations(&alloc), vec![(0, 1), (8, 5), (16, 6), (32, 2)]);
+}
+
+#[test]
+fn merge_relocations_overlapping() {
+    let cx = TargetDataLayout::default();
+    let mut alloc = alloc_with_pointers(64, &[(0, 1), (32, 2)]);
+    let other = alloc_with_pointers(16, &[(0, 5), (8, 6)]);
+    // The relocation shifted to 4 overlaps with the one at 0.
+    let res = alloc.merge_relocations(&cx, other.relocations(), ptr(4));
+    assert!(matches!(
+        err_kind(res),
+        InterpError::Unsupported(UnsupportedOpInfo::PartialPointerOverwrite { .. })
+    ));
+    assert_eq!(relocations(&alloc), vec![(0, 1), (32, 2)]);
+}
+
+#[test]
+fn remap_subset() {
+    let alloc = alloc_with_pointers(64, &[(0, 1), (8, 2), (16, 3)]);
+    let original = alloc.relocations().clone();
+
+    // Neither an empty mapping nor one without any of the targets unshares the map.
+    let mut mapping = FxHashMap::default();
+    let mut remapped = original.clone();
+    remapped.remap_subset(&mapping);
+    assert!(Lrc::ptr_eq(&original.0, &remapped.0));
+    mapping.insert(AllocId(4), AllocId(5));
+    remapped.remap_subset(&mapping);
+    assert!(Lrc::ptr_eq(&original.0, &remapped.0));
+
+    // Targets without an entry in the mapping are kept.
+    mapping.insert(AllocId(2), AllocId(6));
+    remapped.remap_subset(&mapping);
+    let targets: Vec<_> =
+        remapped.iter().map(|&(offset, ((), id))| (offset.bytes(), id.0)).collect();
+    assert_eq!(targets, vec![(0, 1), (8, 6), (16, 3)]);
+    assert_eq!(relocations(&alloc), vec![(0, 1), (8, 2), (16, 3)]);
+}
+
+#[test]
+fn empty_relocations_short_circuit() {
+    let cx = TargetDataLayout::default();
+
+    // Computing the window for this range would overflow, but there is nothing to look up.
+    let relocations = Relocations::<()>::default();
+    assert!(
+        relocations
+            .get_overlapping(&cx, Size::from_bytes(u64::MAX), Size::from_bytes(1))
+            .is_empty()
+    );
+
+    let alloc = alloc_with_pointers(64, &[]);
+    let mut cleared = alloc.clone();
+    let res = cleared.clear_relocations_reporting(&cx, ptr(8), Size::from_bytes(16));
+    assert!(matches!(res, Ok(false)));
+    assert!(Lrc::ptr_eq(&alloc.relocations.0, &cleared.relocations.0));
+}
+
+#[test]
+fn zero_size_ranges() {
+    let cx = TargetDataLayout::default();
+    let mut alloc = alloc_with_pointers(64, &[(4, 1)]);
+
+    // The relocation at 4 overlaps the position of an empty source range, but it is not part
+    // of the copy, however often that is repeated.
+    let copy = alloc.prepare_relocation_copy(&cx, ptr(8), Size::ZERO, ptr(0), 5);
+    let mut dest = alloc_with_pointers(64, &[]);
+    dest.mark_relocation_range(copy.ok().unwrap());
+    assert_eq!(relocations(&dest), vec![]);
+
+    assert!((4..12).all(|i| alloc.init_mask().get(Size::from_bytes(i))));
+    // Clearing an empty range removes the relocation straddling it, like at an edge.
+    assert!(alloc.clear_relocations(&cx, ptr(8), Size::ZERO).is_ok());
+    assert_eq!(relocations(&alloc), vec![]);
+    assert!((4..12).all(|i| !alloc.init_mask().get(Size::from_bytes(i))));
+}
+
+#[test]
+fn empty_relocation_copy_keeps_map_shared() {
+    let cx = TargetDataLayout::default();
+    let alloc = alloc_with_pointers(64, &[(0, 1), (32, 2)]);
+
+    // Copying plain bytes, or a pointer zero times, inserts nothing.
+    let mut copied = alloc.clone();
+    let copy = alloc.prepare_relocation_copy(&cx, ptr(8), Size::from_bytes(16), ptr(40), 1);
+    copied.mark_relocation_range(copy.ok().unwrap());
+    let copy = alloc.prepare_relocation_copy(&cx, ptr(0), Size::from_bytes(16), ptr(40), 0);
+    assert!(copied.mark_relocation_range_at(&cx, &copy.ok().unwrap(), Size::from_bytes(8)).is_ok());
+    assert!(Lrc::ptr_eq(&alloc.relocations.0, &copied.relocations.0));
+    assert_eq!(relocations(&copied), vec![(0, 1), (32, 2)]);
+}
+
+#[test]
+fn validate_offsets_map_at() {
+    let cx = TargetDataLayout::default();
+    let map_at = |offsets: &[u64]| {
+        SortedMap::from_presorted_elements(
+            offsets.iter().map(|&offset| (Size::from_bytes(offset), ((), AllocId(1)))).collect(),
+        )
+    };
+
+    // Adding the pointer size to either offset would overflow.
+    let res = Relocations::from_decoded_validated(&cx, map_at(&[u64::MAX - 6, u64::MAX - 2]));
+    assert!(res.is_err());
+
+    let relocations = Relocations(Lrc::new(map_at(&[u64::MAX - 2])));
+    assert!(relocations.validate(&cx, Size::from_bytes(64)).is_err());
+    assert!(relocations.validate(&cx, Size::from_bytes(u64::MAX)).is_err());
+}
+
+#[test]
+fn first_relocation_zero_size() {
+    let cx = TargetDataLayout::default();
+    let alloc = alloc_with_pointers(64, &[(4, 1), (16, 2)]);
+
+    // The relocation at 4 covers 4..12, so it straddles 8, but it ends exactly at 12.
+    assert_eq!(
+        alloc.first_relocation(&cx, ptr(8), Size::ZERO),
+        Some((Size::from_bytes(4), ((), AllocId(1))))
+    );
+    assert_eq!(alloc.first_relocation(&cx, ptr(12), Size::ZERO), None);
+    // A relocation starting at the offset is not found either, as it is after the boundary.
+    assert_eq!(alloc.first_relocation(&cx, ptr(16), Size::ZERO), None);
+}
+
+#[test]
+fn compact_encoding_round_trip() {
+    let alloc = alloc_with_pointers(64, &[(0, 1), (8, 300), (40, u64::MAX)]);
+    let encoded = alloc.relocations().encode_compact();
+    assert_eq!(Relocations::decode_compact(&encoded).as_ref(), Ok(alloc.relocations()));
+
+    let empty = Relocations::default();
+    assert_eq!(empty.encode_compact(), vec![0]);
+    assert_eq!(Relocations::decode_compact(&[0]), Ok(empty));
+}
+
+#[test]
+fn compact_encoding_invalid() {
+    let decode_err = |bytes: &[u8]| Relocations::decode_compact(bytes).err();
+    let truncated = Some("truncated or overlong LEB128 value".to_string());
+
+    // Truncated input, also in the middle of a value.
+    let encoded = alloc_with_pointers(64, &[(0, 1), (8, 300)]).relocations().encode_compact();
+    assert_eq!(decode_err(&encoded[..encoded.len() - 1]), truncated);
+    assert_eq!(decode_err(&[]), truncated);
+    assert_eq!(decode_err(&[1, 0]), truncated);
+
+    // A value with more than 10 bytes, and a 10th byte with bits beyond the 64th.
+    assert_eq!(decode_err(&[0x80; 11]), truncated);
+    let mut too_big = vec![1, 0];
+    too_big.extend_from_slice(&[0xff; 9]);
+    too_big.push(0x02);
+    assert_eq!(decode_err(&too_big), Some("LEB128 value does not fit into 64 bits".to_string()));
+    // With only the 64th bit set in the 10th byte, the value is `u64::MAX`.
+    *too_big.last_mut().unwrap() = 0x01;
+    let decoded = Relocations::decode_compact(&too_big).ok().unwrap();
+    assert_eq!(decoded.get(&Size::ZERO), Some(&((), AllocId(u64::MAX))));
+
+    assert_eq!(
+        decode_err(&[2, 8, 1, 8, 2]),
+        Some("relocation offsets 8 and 8 are not strictly increasing".to_string())
+    );
+    assert_eq!(
+        decode_err(&[2, 8, 1, 0, 2]),
+        Some("relocation offsets 8 and 0 are not strictly increasing".to_string())
+    );
+
+    assert_eq!(decode_err(&[0, 0]), Some("1 trailing bytes after the relocations".to_string()));
+}
@@ -353,6 +353,13 @@ pub enum UnsupportedOpInfo {
     NoMirFor(DefId),
     /// Encountered a pointer where we needed raw bytes.
     ReadPointerAsBytes,
+    /// Overwriting parts of a pointer.
+    PartialPointerOverwrite {
+        /// The start of the pointer.
+        ptr: Pointer,
+        /// The size of the pointer, i.e., of the memory range that would be partially overwritten.
+        size: Size,
+    },
     //
     // The variants below are only reachable from CTFE/const prop, miri will never emit them.
     //
@@ -372,6 +379,12 @@ fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             ReadExternStatic(did) => write!(f, "cannot read from extern static ({:?})", did),
             NoMirFor(did) => write!(f, "no MIR body is available for {:?}", did),
             ReadPointerAsBytes => write!(f, "unable to turn pointer into raw bytes",),
+            PartialPointerOverwrite { ptr, size } => write!(
+                f,
+                "unable to overwrite parts of the {}-byte pointer in memory at {}",
+                size.bytes(),
+                ptr
+            ),
             ReadBytesAsPointer => write!(f, "unable to turn bytes into a pointer"),
             ThreadLocalStatic(did) => write!(f, "cannot access thread local static ({:?})", did),
         }
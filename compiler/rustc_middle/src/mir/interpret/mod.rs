@@ -125,7 +125,10 @@ macro_rules! throw_machine_stop {
 
 pub use self::value::{get_slice_bytes, ConstAlloc, ConstValue, Scalar, ScalarMaybeUninit};
 
-pub use self::allocation::{Allocation, AllocationExtra, InitMask, Relocations};
+pub use self::allocation::{
+    Allocation, AllocationExtra, AllocationRelocations, FrozenRelocations, InitMask,
+    RelocationStats, Relocations,
+};
 
 pub use self::pointer::{Pointer, PointerArithmetic};
 
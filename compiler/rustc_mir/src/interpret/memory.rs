@@ -924,7 +924,7 @@ pub fn copy_repeatedly(
         // (`get_bytes_with_uninit_and_ptr` below checks that there are no
         // relocations overlapping the edges; those would not be handled correctly).
         let relocations =
-            self.get_raw(src.alloc_id)?.prepare_relocation_copy(self, src, size, dest, length);
+            self.get_raw(src.alloc_id)?.prepare_relocation_copy(self, src, size, dest, length)?;
 
         let tcx = self.tcx;
 